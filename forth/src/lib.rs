@@ -1,17 +1,96 @@
+//! A small Forth-like interpreter with quotations and a custom-word compiler.
+//!
+//! Naming note: quotations add a `call`/`if`/`times` family of combinators
+//! (pop a quotation off a separate quotation stack and run it under some
+//! condition), while the keyword-scanned `if ... else ... then` control flow
+//! also claims the word `if`. Since a word can only resolve to one thing,
+//! the quotation combinator is exposed as `when` (`quot cond when`) rather
+//! than `if`; `call` and `times` keep their original names since they don't
+//! collide with anything.
+
 use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
 
 pub type Value = i32;
 pub type Result = std::result::Result<(), Error>;
 
-#[derive(Debug, PartialEq, Eq)]
+/// The byte offsets (plus derived line/column) of a token within the string
+/// passed to `eval`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Error {
-    DivisionByZero,
-    StackUnderflow,
-    UnknownWord,
-    InvalidWord,
+    DivisionByZero { span: Span },
+    StackUnderflow { span: Span },
+    UnknownWord { span: Span },
+    InvalidWord { span: Span },
 }
 
-#[derive(Debug)]
+impl Error {
+    /// Where in the evaluated input this error occurred.
+    pub fn span(&self) -> Span {
+        match self {
+            Error::DivisionByZero { span }
+            | Error::StackUnderflow { span }
+            | Error::UnknownWord { span }
+            | Error::InvalidWord { span } => *span,
+        }
+    }
+
+    /// Returns the same kind of error, relocated to `span`.
+    fn with_span(self, span: Span) -> Self {
+        match self {
+            Error::DivisionByZero { .. } => Error::DivisionByZero { span },
+            Error::StackUnderflow { .. } => Error::StackUnderflow { span },
+            Error::UnknownWord { .. } => Error::UnknownWord { span },
+            Error::InvalidWord { .. } => Error::InvalidWord { span },
+        }
+    }
+
+    /// Relocates to `span`, but only if this error doesn't already carry a
+    /// real position. Every primitive op (`execute_add`, `execute_dup`, ...)
+    /// raises with `Span::default()` as a placeholder; `execute_ops` fills
+    /// that in with the actual op's span as the error bubbles out. Once it's
+    /// real, outer callers (a custom word call, an `if`/`call`/`times` site)
+    /// must not clobber it with their own, coarser span.
+    fn with_span_if_default(self, span: Span) -> Self {
+        if self.span() == Span::default() {
+            self.with_span(span)
+        } else {
+            self
+        }
+    }
+}
+
+// Equality (and tests built on it) compares only the error kind, ignoring
+// where it happened, so callers who don't care about position can still
+// `assert_eq!` against a bare variant.
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+impl Eq for Error {}
+
+/// A definition body bloating past this many ops (after inlining) is
+/// rejected rather than compiled, guarding against pathological blow-up from
+/// words that redefine each other in a chain.
+const MAX_COMPILED_LEN: usize = 10_000;
+
+#[derive(Debug, Clone)]
 enum Token {
     Int(Value),
     Add,
@@ -25,6 +104,35 @@ enum Token {
     Colon,
     SemiColon,
     Custom(u32),
+    QuoteStart,
+    QuoteEnd,
+    // each element carries the span of that token within the original
+    // input, so an error raised deep inside a quotation or an `if` branch
+    // can still be traced back to the exact word that caused it
+    Quotation(Vec<(Token, Span)>),
+    Call,
+    Times,
+    // quotation combinator: pops a quotation and an integer condition,
+    // running the quotation only when the condition is non-zero. Spelled
+    // `when` rather than `if` so it doesn't collide with the keyword-scanned
+    // `if ... else ... then` below.
+    When,
+    // raw `if` keyword as seen by the tokenizer; `eval`/`create_definition`
+    // resolve it into a structured `Token::If` by scanning ahead for the
+    // matching `else`/`then`
+    IfStart,
+    If {
+        then_branch: Vec<(Token, Span)>,
+        else_branch: Option<Vec<(Token, Span)>>,
+    },
+    Else,
+    Then,
+    Lt,
+    Gt,
+    Eq,
+    Dot,
+    DotS,
+    Emit,
 }
 
 impl Token {
@@ -40,22 +148,77 @@ impl Token {
             "drop" => Some(Self::Drop),
             "swap" => Some(Self::Swap),
             "over" => Some(Self::Over),
+            "[" => Some(Self::QuoteStart),
+            "]" => Some(Self::QuoteEnd),
+            "call" => Some(Self::Call),
+            "times" => Some(Self::Times),
+            "when" => Some(Self::When),
+            "if" => Some(Self::IfStart),
+            "else" => Some(Self::Else),
+            "then" => Some(Self::Then),
+            "<" => Some(Self::Lt),
+            ">" => Some(Self::Gt),
+            "=" => Some(Self::Eq),
+            "." => Some(Self::Dot),
+            ".s" => Some(Self::DotS),
+            "emit" => Some(Self::Emit),
             _ => s.parse::<i32>().map(|v| Self::Int(v)).ok(),
         }
     }
 }
 
+/// A compiled instruction together with the span of the source token it was
+/// compiled from, so a failure while running it can be traced back to where
+/// it came from -- even when it's an op copied in from an inlined custom
+/// word, or nested inside a quotation or `if` branch compiled long ago.
+#[derive(Debug, Clone)]
+struct Op {
+    kind: OpKind,
+    span: Span,
+}
+
+/// A single primitive operation in a compiled definition. Unlike `Token`,
+/// an `OpKind` never references another custom word by id: `CustomWords::compile`
+/// inlines every reference at definition time, so running a word is a flat
+/// loop with no hashmap lookups or recursion through other definitions.
+#[derive(Debug, Clone)]
+enum OpKind {
+    Push(Value),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Dup,
+    Drop,
+    Swap,
+    Over,
+    Lt,
+    Gt,
+    Eq,
+    Dot,
+    DotS,
+    Emit,
+    Quote(Vec<Op>),
+    Call,
+    Times,
+    When,
+    If {
+        then_branch: Vec<Op>,
+        else_branch: Option<Vec<Op>>,
+    },
+}
+
 #[derive(Debug)]
 struct CustomWords {
     word_ids: HashMap<String, u32>,
-    custom_words: HashMap<u32, Vec<Token>>,
+    compiled: HashMap<u32, Vec<Op>>,
 }
 
 impl CustomWords {
     fn new() -> Self {
         Self {
             word_ids: HashMap::new(),
-            custom_words: HashMap::new(),
+            compiled: HashMap::new(),
         }
     }
 
@@ -63,13 +226,13 @@ impl CustomWords {
         self.word_ids.contains_key(k)
     }
 
-    fn get_tokens(&self, k: &str) -> Option<&Vec<Token>> {
+    fn get_ops(&self, k: &str) -> Option<&Vec<Op>> {
         let id = self.word_ids.get(k)?;
-        self.custom_words.get(id)
+        self.compiled.get(id)
     }
 
-    fn get_by_id(&self, id: &u32) -> Option<&Vec<Token>> {
-        self.custom_words.get(id)
+    fn get_ops_by_id(&self, id: &u32) -> Option<&Vec<Op>> {
+        self.compiled.get(id)
     }
 
     fn get_custom_token(&self, k: &str) -> Option<Token> {
@@ -77,41 +240,262 @@ impl CustomWords {
         Some(Token::Custom(*id))
     }
 
-    fn rename_word(&mut self, k: &str) -> Result {
-        let id = self.word_ids.remove(k).ok_or(Error::UnknownWord)?;
-        self.word_ids.insert(format!("_{k}"), id);
-        Ok(())
+    /// Compiles a parsed, spanned token list into a flat `Vec<Op>`, inlining
+    /// every `Token::Custom` reference by copying that word's
+    /// already-compiled ops (spans and all). Because the copy happens now
+    /// rather than at call time, redefining a word later can never change
+    /// what an earlier definition runs.
+    fn compile(&self, tokens: &[(Token, Span)]) -> std::result::Result<Vec<Op>, Error> {
+        let mut budget = MAX_COMPILED_LEN;
+        self.compile_within_budget(tokens, &mut budget)
     }
 
-    fn insert_word(&mut self, k: &str, tokens: Vec<Token>) -> Result {
-        // if the word already exists in the map then update the map to keep the old word reference valid
-        if self.is_known_word(k) {
-            // rename the existing word first
-            self.rename_word(k)?;
+    /// Does the actual compiling, charging every op it produces -- including
+    /// ops copied in from an inlined custom word, and ops nested inside a
+    /// quotation or an `if` branch -- against one `budget` shared across the
+    /// whole recursive call tree. A quotation or branch only ever costs 1 op
+    /// *at its own nesting level* (the single `OpKind::Quote`/`OpKind::If` it folds
+    /// into), so without a budget threaded through the recursion that's all
+    /// `MAX_COMPILED_LEN` would ever see; sharing one counter end-to-end is
+    /// what makes the cap bound the real, fully-inlined op count.
+    fn compile_within_budget(
+        &self,
+        tokens: &[(Token, Span)],
+        budget: &mut usize,
+    ) -> std::result::Result<Vec<Op>, Error> {
+        let charge = |budget: &mut usize, n: usize, span: Span| -> std::result::Result<(), Error> {
+            *budget = budget.checked_sub(n).ok_or(Error::InvalidWord { span })?;
+            Ok(())
+        };
+
+        let mut ops = vec![];
+        for (token, span) in tokens {
+            let span = *span;
+            let kind = match token {
+                Token::Int(n) => OpKind::Push(*n),
+                Token::Add => OpKind::Add,
+                Token::Sub => OpKind::Sub,
+                Token::Mul => OpKind::Mul,
+                Token::Div => OpKind::Div,
+                Token::Dup => OpKind::Dup,
+                Token::Drop => OpKind::Drop,
+                Token::Swap => OpKind::Swap,
+                Token::Over => OpKind::Over,
+                Token::Lt => OpKind::Lt,
+                Token::Gt => OpKind::Gt,
+                Token::Eq => OpKind::Eq,
+                Token::Dot => OpKind::Dot,
+                Token::DotS => OpKind::DotS,
+                Token::Emit => OpKind::Emit,
+                Token::Call => OpKind::Call,
+                Token::Times => OpKind::Times,
+                Token::When => OpKind::When,
+                Token::Quotation(inner) => {
+                    let inner_ops = self.compile_within_budget(inner, budget)?;
+                    OpKind::Quote(inner_ops)
+                }
+                Token::If {
+                    then_branch,
+                    else_branch,
+                } => {
+                    let then_ops = self.compile_within_budget(then_branch, budget)?;
+                    let else_ops = else_branch
+                        .as_ref()
+                        .map(|b| self.compile_within_budget(b, budget))
+                        .transpose()?;
+                    OpKind::If {
+                        then_branch: then_ops,
+                        else_branch: else_ops,
+                    }
+                }
+                Token::Custom(id) => {
+                    let inlined = self
+                        .get_ops_by_id(id)
+                        .ok_or(Error::UnknownWord { span })?;
+                    charge(budget, inlined.len(), span)?;
+                    ops.extend(inlined.iter().cloned());
+                    continue;
+                }
+                Token::Colon
+                | Token::SemiColon
+                | Token::QuoteStart
+                | Token::QuoteEnd
+                | Token::IfStart
+                | Token::Else
+                | Token::Then => return Err(Error::InvalidWord { span }),
+            };
+            charge(budget, 1, span)?;
+            ops.push(Op { kind, span });
         }
+        Ok(ops)
+    }
+
+    fn insert_word(&mut self, k: &str, tokens: Vec<(Token, Span)>, span: Span) -> Result {
         // custom word name cannot be a number
         if k.parse::<i32>().is_ok() {
-            return Err(Error::InvalidWord);
+            return Err(Error::InvalidWord { span });
         }
-        let id = self.word_ids.len() as u32 + 1;
+        let compiled = self.compile(&tokens).map_err(|e| e.with_span_if_default(span))?;
+        // redefining a word just points its name at a fresh id, so any
+        // earlier definition that already inlined the old id keeps running
+        // against its own frozen copy of the old ops
+        let id = self.compiled.len() as u32 + 1;
         self.word_ids.insert(k.to_string(), id);
-        self.custom_words.insert(id, tokens);
+        self.compiled.insert(id, compiled);
 
         Ok(())
     }
 }
 
+/// Splits `input` into whitespace-separated words paired with their byte
+/// offset, line, and column, so later errors can report where they happened.
+fn scan(input: &str) -> Vec<(&str, Span)> {
+    let bytes = input.as_bytes();
+    let len = bytes.len();
+    let mut out = vec![];
+    let mut idx = 0;
+    let mut line = 1;
+    let mut column = 1;
+
+    while idx < len {
+        while idx < len && bytes[idx].is_ascii_whitespace() {
+            if bytes[idx] == b'\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+            idx += 1;
+        }
+        if idx >= len {
+            break;
+        }
+
+        let start = idx;
+        let (start_line, start_column) = (line, column);
+        while idx < len && !bytes[idx].is_ascii_whitespace() {
+            idx += 1;
+            column += 1;
+        }
+
+        out.push((
+            &input[start..idx],
+            Span {
+                start,
+                end: idx,
+                line: start_line,
+                column: start_column,
+            },
+        ));
+    }
+
+    out
+}
+
+/// Collects the tokens of a `[ ... ]` quotation, recursing into nested
+/// quotations so that the bracket depth is always tracked by the call stack
+/// rather than by hand. Each token keeps the span it had in the original
+/// input, so an error raised once this quotation is compiled and run can
+/// still point at the exact word that caused it.
+fn collect_quotation<'a>(
+    custom_words: &CustomWords,
+    iter: &mut impl Iterator<Item = (&'a str, Span)>,
+) -> std::result::Result<Vec<(Token, Span)>, Error> {
+    let mut tokens = vec![];
+    while let Some((t, span)) = iter.next() {
+        if custom_words.is_known_word(t) {
+            let token = custom_words
+                .get_custom_token(t)
+                .ok_or(Error::InvalidWord { span })?;
+            tokens.push((token, span));
+            continue;
+        }
+
+        let token = Token::tokenize(t).ok_or(Error::InvalidWord { span })?;
+        match token {
+            Token::QuoteStart => {
+                let inner = collect_quotation(custom_words, iter)?;
+                tokens.push((Token::Quotation(inner), span));
+            }
+            Token::QuoteEnd => return Ok(tokens),
+            Token::IfStart => {
+                let structured = read_if_branches(custom_words, iter)?;
+                tokens.push((structured, span));
+            }
+            _ => tokens.push((token, span)),
+        }
+    }
+
+    // ran out of input before the matching `]`
+    Err(Error::InvalidWord {
+        span: Span::default(),
+    })
+}
+
+/// Scans ahead from a just-consumed `if` for its matching `else`/`then`,
+/// respecting nesting so an inner `if` doesn't confuse the matcher, and
+/// builds the structured `Token::If`.
+fn read_if_branches<'a>(
+    custom_words: &CustomWords,
+    iter: &mut impl Iterator<Item = (&'a str, Span)>,
+) -> std::result::Result<Token, Error> {
+    let mut then_branch = vec![];
+    let mut else_branch: Option<Vec<(Token, Span)>> = None;
+
+    loop {
+        let (t, span) = iter.next().ok_or(Error::InvalidWord {
+            span: Span::default(),
+        })?;
+
+        let token = if custom_words.is_known_word(t) {
+            custom_words
+                .get_custom_token(t)
+                .ok_or(Error::InvalidWord { span })?
+        } else {
+            Token::tokenize(t).ok_or(Error::InvalidWord { span })?
+        };
+
+        let resolved = match token {
+            Token::IfStart => {
+                // nested if: resolve its own matching else/then first
+                read_if_branches(custom_words, iter)?
+            }
+            Token::QuoteStart => Token::Quotation(collect_quotation(custom_words, iter)?),
+            Token::Else if else_branch.is_none() => {
+                else_branch = Some(vec![]);
+                continue;
+            }
+            Token::Then => {
+                return Ok(Token::If {
+                    then_branch,
+                    else_branch,
+                });
+            }
+            other => other,
+        };
+
+        match &mut else_branch {
+            Some(b) => b.push((resolved, span)),
+            None => then_branch.push((resolved, span)),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Forth {
     stack: Vec<Value>,
+    quotations: Vec<Vec<Op>>,
     custom_words: CustomWords,
+    output: Vec<u8>,
 }
 
 impl Forth {
     pub fn new() -> Self {
         Self {
             stack: vec![],
+            quotations: vec![],
             custom_words: CustomWords::new(),
+            output: vec![],
         }
     }
 
@@ -119,80 +503,201 @@ impl Forth {
         self.stack.as_slice()
     }
 
+    /// Bytes written by `.`, `.s`, and `emit` since the last time output was
+    /// taken.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Drains and returns the bytes written by `.`, `.s`, and `emit`.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Runs an interactive REPL over the given input/output streams,
+    /// evaluating one line at a time and printing the resulting stack after
+    /// each line that evaluates successfully, the way the kcats command-line
+    /// REPL does; a line that fails prints its error instead. The stack is
+    /// kept intact between lines.
+    pub fn run_repl<R: BufRead, W: Write>(&mut self, mut input: R, mut output: W) -> io::Result<()> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                break;
+            }
+            match self.eval(&line) {
+                Ok(()) => {
+                    output.write_all(&self.take_output())?;
+                    // print the stack left over after this line, the way
+                    // the kcats REPL prints it after every successful input
+                    for v in self.stack() {
+                        write!(output, "{v} ")?;
+                    }
+                    writeln!(output, "ok")?;
+                }
+                Err(e) => {
+                    // flush whatever `.`/`.s`/`emit` already wrote before the
+                    // error, so it doesn't linger and leak into the next
+                    // successful line's output
+                    output.write_all(&self.take_output())?;
+                    writeln!(output, "{e:?}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the REPL against the process's standard input and output.
+    pub fn run_stdin(&mut self) -> io::Result<()> {
+        let stdin = io::stdin();
+        self.run_repl(stdin.lock(), io::stdout())
+    }
+
     pub fn eval(&mut self, input: &str) -> Result {
         let input = input.to_ascii_lowercase();
-        let mut iter = input.split_ascii_whitespace();
-        while let Some(t) = iter.next() {
+        let tokens = scan(&input);
+        let mut iter = tokens.into_iter();
+        while let Some((t, span)) = iter.next() {
             if self.custom_words.is_known_word(t) {
-                let _ = execute_custom_word(t, &self.custom_words, &mut self.stack)?;
+                execute_custom_word(
+                    t,
+                    span,
+                    &self.custom_words,
+                    &mut self.stack,
+                    &mut self.quotations,
+                    &mut self.output,
+                )?;
                 continue;
             }
 
-            let token = Token::tokenize(t).ok_or(Error::UnknownWord)?;
+            let token = Token::tokenize(t).ok_or(Error::UnknownWord { span })?;
             match token {
                 Token::Colon => self.create_definition(&mut iter)?,
-                _ => execute_token(&token, &self.custom_words, &mut self.stack)?,
+                Token::QuoteStart => {
+                    let quotation = collect_quotation(&self.custom_words, &mut iter)?;
+                    execute_token(
+                        &Token::Quotation(quotation),
+                        span,
+                        &self.custom_words,
+                        &mut self.stack,
+                        &mut self.quotations,
+                        &mut self.output,
+                    )?;
+                }
+                Token::IfStart => {
+                    let structured = read_if_branches(&self.custom_words, &mut iter)?;
+                    execute_token(
+                        &structured,
+                        span,
+                        &self.custom_words,
+                        &mut self.stack,
+                        &mut self.quotations,
+                        &mut self.output,
+                    )?;
+                }
+                _ => execute_token(
+                    &token,
+                    span,
+                    &self.custom_words,
+                    &mut self.stack,
+                    &mut self.quotations,
+                    &mut self.output,
+                )?,
             };
         }
 
         Ok(())
     }
 
-    fn create_definition<'a>(&mut self, iter: &mut impl Iterator<Item = &'a str>) -> Result {
+    fn create_definition<'a>(
+        &mut self,
+        iter: &mut impl Iterator<Item = (&'a str, Span)>,
+    ) -> Result {
         // expect the custom word name
-        let word = iter.next().ok_or(Error::InvalidWord)?;
+        let (word, word_span) = iter.next().ok_or(Error::InvalidWord {
+            span: Span::default(),
+        })?;
 
-        // collect all tokens in the vector
+        // collect all tokens in the vector, each paired with its own span
         let mut tokens = vec![];
-        while let Some(t) = iter.next() {
+        while let Some((t, span)) = iter.next() {
             if self.custom_words.is_known_word(t) {
                 // create Custom token if it is a known word
                 let token = self
                     .custom_words
                     .get_custom_token(t)
-                    .ok_or(Error::InvalidWord)?;
+                    .ok_or(Error::InvalidWord { span })?;
                 // collect the Custom token in the token vec
-                tokens.push(token);
+                tokens.push((token, span));
                 continue;
             }
 
             // parse the string to a valid token
-            let token = Token::tokenize(t).ok_or(Error::InvalidWord)?;
+            let token = Token::tokenize(t).ok_or(Error::InvalidWord { span })?;
             match token {
                 // if semi colon is received then we must break the loop
                 Token::SemiColon => {
-                    tokens.push(token);
+                    tokens.push((token, span));
                     break;
                 }
                 // if another colon is received then it is error
-                Token::Colon => return Err(Error::InvalidWord),
+                Token::Colon => return Err(Error::InvalidWord { span }),
+                // quotations are collected whole and stored verbatim
+                Token::QuoteStart => {
+                    let quotation = collect_quotation(&self.custom_words, iter)?;
+                    tokens.push((Token::Quotation(quotation), span));
+                }
+                // if/else/then is resolved to a structured Token::If up front
+                Token::IfStart => {
+                    let structured = read_if_branches(&self.custom_words, iter)?;
+                    tokens.push((structured, span));
+                }
                 // push all other valid tokens in the vec
-                _ => tokens.push(token),
+                _ => tokens.push((token, span)),
             }
         }
         // pop the last inserted semi colon and also check the token vec is not empty
         // last token in the vec must always be semi colon
-        match tokens.pop().ok_or(Error::InvalidWord)? {
-            Token::SemiColon => {}
-            _ => return Err(Error::InvalidWord),
+        match tokens.pop().ok_or(Error::InvalidWord {
+            span: Span::default(),
+        })? {
+            (Token::SemiColon, _) => {}
+            _ => {
+                return Err(Error::InvalidWord {
+                    span: Span::default(),
+                })
+            }
         };
 
-        // insert the new custom word to known_word list
-        self.custom_words.insert_word(word, tokens)
+        // compile and insert the new custom word into the known_word list
+        self.custom_words.insert_word(word, tokens, word_span)
     }
 }
 
-fn execute_custom_word(token: &str, custom_words: &CustomWords, stack: &mut Vec<Value>) -> Result {
-    let tokens = custom_words.get_tokens(token).ok_or(Error::UnknownWord)?;
-    for token in tokens {
-        let _ = execute_token(token, custom_words, stack)?;
-    }
-
-    Ok(())
+fn execute_custom_word(
+    token: &str,
+    span: Span,
+    custom_words: &CustomWords,
+    stack: &mut Vec<Value>,
+    quotations: &mut Vec<Vec<Op>>,
+    output: &mut Vec<u8>,
+) -> Result {
+    let ops = custom_words
+        .get_ops(token)
+        .ok_or(Error::UnknownWord { span })?;
+    execute_ops(ops, stack, quotations, output).map_err(|e| e.with_span_if_default(span))
 }
 
-fn execute_token(token: &Token, custom_words: &CustomWords, stack: &mut Vec<Value>) -> Result {
-    match token {
+fn execute_token(
+    token: &Token,
+    span: Span,
+    custom_words: &CustomWords,
+    stack: &mut Vec<Value>,
+    quotations: &mut Vec<Vec<Op>>,
+    output: &mut Vec<u8>,
+) -> Result {
+    let result = match token {
         Token::Add => execute_add(stack),
         Token::Sub => execute_sub(stack),
         Token::Mul => execute_mul(stack),
@@ -201,38 +706,116 @@ fn execute_token(token: &Token, custom_words: &CustomWords, stack: &mut Vec<Valu
         Token::Drop => execute_drop(stack),
         Token::Swap => execute_swap(stack),
         Token::Over => execute_over(stack),
+        Token::Lt => execute_lt(stack),
+        Token::Gt => execute_gt(stack),
+        Token::Eq => execute_eq(stack),
         Token::Int(n) => execute_int(stack, *n),
-        Token::Custom(id) => execute_custom_token(id, custom_words, stack),
-        _ => Err(Error::UnknownWord),
+        Token::Dot => execute_dot(stack, output),
+        Token::DotS => execute_dot_s(stack, output),
+        Token::Emit => execute_emit(stack, output),
+        Token::Custom(id) => {
+            let ops = custom_words
+                .get_ops_by_id(id)
+                .ok_or(Error::UnknownWord { span })?;
+            execute_ops(ops, stack, quotations, output)
+        }
+        Token::Quotation(tokens) => custom_words.compile(tokens).map(|ops| {
+            quotations.push(ops);
+        }),
+        Token::Call => execute_call(stack, quotations, output),
+        Token::Times => execute_times(stack, quotations, output),
+        Token::When => execute_when(stack, quotations, output),
+        Token::If {
+            then_branch,
+            else_branch,
+        } => custom_words.compile(then_branch).and_then(|then_ops| {
+            let else_ops = else_branch
+                .as_ref()
+                .map(|b| custom_words.compile(b))
+                .transpose()?;
+            execute_if(&then_ops, &else_ops, stack, quotations, output)
+        }),
+        _ => Err(Error::UnknownWord { span }),
+    };
+    result.map_err(|e| e.with_span_if_default(span))
+}
+
+/// Runs a flat, already-compiled op stream. No hashmap lookups or recursion
+/// through other custom words happen here; only control-flow bodies
+/// (quotations, if/else, times) recurse, one level per body running inside
+/// another. Each op carries the real span of the token it was compiled
+/// from, so an error deep inside an inlined word or an `if` branch is
+/// reported there rather than at the call site that triggered it.
+fn execute_ops(
+    ops: &[Op],
+    stack: &mut Vec<Value>,
+    quotations: &mut Vec<Vec<Op>>,
+    output: &mut Vec<u8>,
+) -> Result {
+    for op in ops {
+        let result = match &op.kind {
+            OpKind::Push(n) => execute_int(stack, *n),
+            OpKind::Add => execute_add(stack),
+            OpKind::Sub => execute_sub(stack),
+            OpKind::Mul => execute_mul(stack),
+            OpKind::Div => execute_div(stack),
+            OpKind::Dup => execute_dup(stack),
+            OpKind::Drop => execute_drop(stack),
+            OpKind::Swap => execute_swap(stack),
+            OpKind::Over => execute_over(stack),
+            OpKind::Lt => execute_lt(stack),
+            OpKind::Gt => execute_gt(stack),
+            OpKind::Eq => execute_eq(stack),
+            OpKind::Dot => execute_dot(stack, output),
+            OpKind::DotS => execute_dot_s(stack, output),
+            OpKind::Emit => execute_emit(stack, output),
+            OpKind::Quote(quoted) => {
+                quotations.push(quoted.clone());
+                Ok(())
+            }
+            OpKind::Call => execute_call(stack, quotations, output),
+            OpKind::Times => execute_times(stack, quotations, output),
+            OpKind::When => execute_when(stack, quotations, output),
+            OpKind::If {
+                then_branch,
+                else_branch,
+            } => execute_if(then_branch, else_branch, stack, quotations, output),
+        };
+        result.map_err(|e| e.with_span_if_default(op.span))?;
     }
+    Ok(())
 }
 
 fn execute_add(stack: &mut Vec<Value>) -> Result {
-    let a = stack.pop().ok_or(Error::StackUnderflow)?;
-    let b = stack.pop().ok_or(Error::StackUnderflow)?;
+    let span = Span::default();
+    let a = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    let b = stack.pop().ok_or(Error::StackUnderflow { span })?;
     stack.push(a + b);
     Ok(())
 }
 
 fn execute_sub(stack: &mut Vec<Value>) -> Result {
-    let a = stack.pop().ok_or(Error::StackUnderflow)?;
-    let b = stack.pop().ok_or(Error::StackUnderflow)?;
+    let span = Span::default();
+    let a = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    let b = stack.pop().ok_or(Error::StackUnderflow { span })?;
     stack.push(b - a);
     Ok(())
 }
 
 fn execute_mul(stack: &mut Vec<Value>) -> Result {
-    let a = stack.pop().ok_or(Error::StackUnderflow)?;
-    let b = stack.pop().ok_or(Error::StackUnderflow)?;
+    let span = Span::default();
+    let a = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    let b = stack.pop().ok_or(Error::StackUnderflow { span })?;
     stack.push(b * a);
     Ok(())
 }
 
 fn execute_div(stack: &mut Vec<Value>) -> Result {
-    let a = stack.pop().ok_or(Error::StackUnderflow)?;
-    let b = stack.pop().ok_or(Error::StackUnderflow)?;
+    let span = Span::default();
+    let a = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    let b = stack.pop().ok_or(Error::StackUnderflow { span })?;
     if a == 0 {
-        Err(Error::DivisionByZero)
+        Err(Error::DivisionByZero { span })
     } else {
         stack.push(b / a);
         Ok(())
@@ -240,28 +823,32 @@ fn execute_div(stack: &mut Vec<Value>) -> Result {
 }
 
 fn execute_dup(stack: &mut Vec<Value>) -> Result {
-    let a = stack.pop().ok_or(Error::StackUnderflow)?;
+    let span = Span::default();
+    let a = stack.pop().ok_or(Error::StackUnderflow { span })?;
     stack.push(a);
     stack.push(a);
     Ok(())
 }
 
 fn execute_drop(stack: &mut Vec<Value>) -> Result {
-    let _ = stack.pop().ok_or(Error::StackUnderflow)?;
+    let span = Span::default();
+    let _ = stack.pop().ok_or(Error::StackUnderflow { span })?;
     Ok(())
 }
 
 fn execute_swap(stack: &mut Vec<Value>) -> Result {
-    let a = stack.pop().ok_or(Error::StackUnderflow)?;
-    let b = stack.pop().ok_or(Error::StackUnderflow)?;
+    let span = Span::default();
+    let a = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    let b = stack.pop().ok_or(Error::StackUnderflow { span })?;
     stack.push(a);
     stack.push(b);
     Ok(())
 }
 
 fn execute_over(stack: &mut Vec<Value>) -> Result {
-    let a = stack.pop().ok_or(Error::StackUnderflow)?;
-    let b = stack.pop().ok_or(Error::StackUnderflow)?;
+    let span = Span::default();
+    let a = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    let b = stack.pop().ok_or(Error::StackUnderflow { span })?;
     stack.push(b);
     stack.push(a);
     stack.push(b);
@@ -273,10 +860,111 @@ fn execute_int(stack: &mut Vec<Value>, n: Value) -> Result {
     Ok(())
 }
 
-fn execute_custom_token(id: &u32, custom_words: &CustomWords, stack: &mut Vec<Value>) -> Result {
-    let tokens = custom_words.get_by_id(id).ok_or(Error::UnknownWord)?;
-    for token in tokens {
-        execute_token(token, custom_words, stack)?;
+// comparisons push Forth's boolean convention: -1 for true, 0 for false
+fn execute_lt(stack: &mut Vec<Value>) -> Result {
+    let span = Span::default();
+    let a = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    let b = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    stack.push(if b < a { -1 } else { 0 });
+    Ok(())
+}
+
+fn execute_gt(stack: &mut Vec<Value>) -> Result {
+    let span = Span::default();
+    let a = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    let b = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    stack.push(if b > a { -1 } else { 0 });
+    Ok(())
+}
+
+fn execute_eq(stack: &mut Vec<Value>) -> Result {
+    let span = Span::default();
+    let a = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    let b = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    stack.push(if b == a { -1 } else { 0 });
+    Ok(())
+}
+
+// `.` pops the top value and emits its decimal representation.
+fn execute_dot(stack: &mut Vec<Value>, output: &mut Vec<u8>) -> Result {
+    let span = Span::default();
+    let v = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    write!(output, "{v} ").expect("writing to an in-memory buffer cannot fail");
+    Ok(())
+}
+
+// `.s` prints the whole stack, bottom to top, without consuming it.
+fn execute_dot_s(stack: &[Value], output: &mut Vec<u8>) -> Result {
+    for v in stack {
+        write!(output, "{v} ").expect("writing to an in-memory buffer cannot fail");
+    }
+    Ok(())
+}
+
+// `emit` pops the top value and writes it out as a single character.
+fn execute_emit(stack: &mut Vec<Value>, output: &mut Vec<u8>) -> Result {
+    let span = Span::default();
+    let v = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    output.push(v as u8);
+    Ok(())
+}
+
+// `call` pops a quotation and runs it immediately.
+fn execute_call(
+    stack: &mut Vec<Value>,
+    quotations: &mut Vec<Vec<Op>>,
+    output: &mut Vec<u8>,
+) -> Result {
+    let span = Span::default();
+    let ops = quotations.pop().ok_or(Error::StackUnderflow { span })?;
+    execute_ops(&ops, stack, quotations, output)
+}
+
+// `when` pops a quotation and a condition, running the quotation only if the
+// condition is non-zero.
+fn execute_when(
+    stack: &mut Vec<Value>,
+    quotations: &mut Vec<Vec<Op>>,
+    output: &mut Vec<u8>,
+) -> Result {
+    let span = Span::default();
+    let ops = quotations.pop().ok_or(Error::StackUnderflow { span })?;
+    let cond = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    if cond != 0 {
+        execute_ops(&ops, stack, quotations, output)?;
+    }
+    Ok(())
+}
+
+// structured `if ... else ... then`: pops one condition and runs the
+// matching branch.
+fn execute_if(
+    then_branch: &[Op],
+    else_branch: &Option<Vec<Op>>,
+    stack: &mut Vec<Value>,
+    quotations: &mut Vec<Vec<Op>>,
+    output: &mut Vec<u8>,
+) -> Result {
+    let span = Span::default();
+    let cond = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    match (cond != 0, else_branch) {
+        (true, _) => execute_ops(then_branch, stack, quotations, output),
+        (false, Some(ops)) => execute_ops(ops, stack, quotations, output),
+        (false, None) => Ok(()),
+    }
+}
+
+// `times` pops a quotation and a count, running the quotation that many times.
+fn execute_times(
+    stack: &mut Vec<Value>,
+    quotations: &mut Vec<Vec<Op>>,
+    output: &mut Vec<u8>,
+) -> Result {
+    let span = Span::default();
+    let ops = quotations.pop().ok_or(Error::StackUnderflow { span })?;
+    let n = stack.pop().ok_or(Error::StackUnderflow { span })?;
+    for _ in 0..n {
+        execute_ops(&ops, stack, quotations, output)?;
     }
     Ok(())
 }
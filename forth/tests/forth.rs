@@ -0,0 +1,226 @@
+use forth::{Error, Forth};
+
+#[test]
+fn call_runs_a_quotation_pushed_onto_the_quotation_stack() {
+    let mut f = Forth::new();
+    f.eval("[ 1 2 + ] call").unwrap();
+    assert_eq!(f.stack(), [3]);
+}
+
+#[test]
+fn times_runs_a_quotation_the_given_number_of_times() {
+    let mut f = Forth::new();
+    f.eval("0 [ 1 + ] 5 times").unwrap();
+    assert_eq!(f.stack(), [5]);
+}
+
+// Quotation combinator from chunk0-1, exposed as `when` rather than `if`
+// because the keyword-scanned `if ... else ... then` already claims `if`.
+#[test]
+fn when_runs_the_quotation_only_if_the_condition_is_non_zero() {
+    let mut f = Forth::new();
+    f.eval("[ 42 ] 1 when").unwrap();
+    assert_eq!(f.stack(), [42]);
+
+    let mut f = Forth::new();
+    f.eval("[ 42 ] 0 when").unwrap();
+    assert_eq!(f.stack(), [] as [i32; 0]);
+}
+
+#[test]
+fn if_else_then_runs_the_then_branch_when_the_condition_is_non_zero() {
+    let mut f = Forth::new();
+    f.eval("1 if 10 else 20 then").unwrap();
+    assert_eq!(f.stack(), [10]);
+}
+
+#[test]
+fn if_else_then_runs_the_else_branch_when_the_condition_is_zero() {
+    let mut f = Forth::new();
+    f.eval("0 if 10 else 20 then").unwrap();
+    assert_eq!(f.stack(), [20]);
+}
+
+#[test]
+fn if_then_without_an_else_branch_is_a_no_op_when_the_condition_is_zero() {
+    let mut f = Forth::new();
+    f.eval("0 if 10 then").unwrap();
+    assert_eq!(f.stack(), [] as [i32; 0]);
+}
+
+#[test]
+fn comparisons_push_true_as_minus_one_and_false_as_zero() {
+    let mut f = Forth::new();
+    f.eval("1 2 < 2 1 < 1 1 = 2 1 >").unwrap();
+    assert_eq!(f.stack(), [-1, 0, -1, -1]);
+}
+
+#[test]
+fn comparisons_compose_with_if_else_then() {
+    let mut f = Forth::new();
+    f.eval("3 4 < if 1 else 0 then").unwrap();
+    assert_eq!(f.stack(), [1]);
+}
+
+#[test]
+fn call_on_an_empty_quotation_stack_is_a_stack_underflow() {
+    let mut f = Forth::new();
+    assert_eq!(f.eval("call"), Err(Error::StackUnderflow { span: Default::default() }));
+}
+
+// `insert_word` compiles a definition by inlining every word it already
+// knows; redefining one of those words later must not retroactively change
+// what the earlier definition runs.
+#[test]
+fn redefining_a_word_does_not_change_earlier_definitions_using_it() {
+    let mut f = Forth::new();
+    f.eval(": foo 1 ;").unwrap();
+    f.eval(": bar foo ;").unwrap();
+    f.eval(": foo 2 ;").unwrap();
+
+    f.eval("bar").unwrap();
+    f.eval("foo").unwrap();
+
+    assert_eq!(f.stack(), [1, 2]);
+}
+
+// Each doubling roughly doubles the compiled op count (`w w`), so this
+// climbs well past MAX_COMPILED_LEN within a couple dozen definitions,
+// guarding against the kind of chained-redefinition blow-up the compiler's
+// size cap exists for.
+#[test]
+fn a_definition_that_inlines_past_the_compiled_length_cap_is_rejected() {
+    let mut f = Forth::new();
+    f.eval(": w0 1 1 + ;").unwrap();
+
+    let mut last_good = 0;
+    for n in 1..20 {
+        let def = format!(": w{n} w{} w{} ;", n - 1, n - 1);
+        match f.eval(&def) {
+            Ok(()) => last_good = n,
+            Err(_) => {
+                assert!(last_good > 0, "blew up on the very first doubling");
+                return;
+            }
+        }
+    }
+    panic!("expected compilation to be rejected before reaching w19");
+}
+
+// A quotation or `if` branch folds into a single `OpKind::Quote`/`OpKind::If` at its
+// own nesting level, so the cap must be enforced against a budget shared
+// across the whole recursive compile, not reset at each level -- otherwise
+// chaining enough individually-small quotations inlines far more than
+// MAX_COMPILED_LEN ops while looking cheap from the top.
+#[test]
+fn the_compiled_length_cap_is_charged_cumulatively_across_nested_quotations() {
+    let mut f = Forth::new();
+    f.eval(": w0 1 1 + ;").unwrap();
+    for n in 1..=10 {
+        let def = format!(": w{n} w{} w{} ;", n - 1, n - 1);
+        f.eval(&def).unwrap();
+    }
+
+    let mut bomb = String::from(": bomb ");
+    for _ in 0..50 {
+        bomb.push_str("[ w10 ] ");
+    }
+    bomb.push_str("drop ;");
+
+    assert!(f.eval(&bomb).is_err());
+}
+
+#[test]
+fn unknown_word_error_reports_its_byte_offset() {
+    let mut f = Forth::new();
+    let err = f.eval("1 2 foo").unwrap_err();
+
+    assert_eq!(err, Error::UnknownWord { span: Default::default() });
+    let span = err.span();
+    assert_eq!((span.start, span.end), (4, 7));
+    assert_eq!((span.line, span.column), (1, 5));
+}
+
+// `scan` tracks line/column as it walks the input, so an error on a later
+// line must report that line, not just the overall byte offset.
+#[test]
+fn error_span_tracks_line_and_column_across_newlines() {
+    let mut f = Forth::new();
+    let err = f.eval("1 2\nfoo").unwrap_err();
+
+    let span = err.span();
+    assert_eq!((span.start, span.end), (4, 7));
+    assert_eq!((span.line, span.column), (2, 1));
+}
+
+#[test]
+fn division_by_zero_span_points_at_the_division_token() {
+    let mut f = Forth::new();
+    let err = f.eval("6 0 /").unwrap_err();
+
+    assert_eq!(err, Error::DivisionByZero { span: Default::default() });
+    let span = err.span();
+    assert_eq!((span.start, span.end), (4, 5));
+}
+
+// `execute_ops` carries the real span of each compiled op, so an error deep
+// inside an inlined custom word is reported at the failing sub-token, not at
+// the span of the call site that triggered it.
+#[test]
+fn division_by_zero_inside_a_custom_word_points_at_the_slash_in_its_definition() {
+    let mut f = Forth::new();
+    f.eval(": foo 1 0 / ;").unwrap();
+    let err = f.eval("foo").unwrap_err();
+
+    assert_eq!(err, Error::DivisionByZero { span: Default::default() });
+    let span = err.span();
+    assert_eq!((span.start, span.end), (10, 11));
+}
+
+// Same for an `if` branch: the span must point inside the branch, not at the
+// `if` token that selected it.
+#[test]
+fn division_by_zero_inside_an_if_branch_points_at_the_slash_not_the_if() {
+    let mut f = Forth::new();
+    let err = f.eval("-1 if 1 0 / then").unwrap_err();
+
+    assert_eq!(err, Error::DivisionByZero { span: Default::default() });
+    let span = err.span();
+    assert_eq!((span.start, span.end), (10, 11));
+}
+
+#[test]
+fn run_repl_prints_the_stack_after_each_successful_line() {
+    let mut f = Forth::new();
+    let mut out = Vec::new();
+    f.run_repl(std::io::Cursor::new(b"1 2 +\n".as_ref()), &mut out)
+        .unwrap();
+
+    assert_eq!(out, b"3 ok\n");
+}
+
+#[test]
+fn run_repl_still_emits_manually_written_output_before_the_stack() {
+    let mut f = Forth::new();
+    let mut out = Vec::new();
+    f.run_repl(std::io::Cursor::new(b"42 dup . .\n".as_ref()), &mut out)
+        .unwrap();
+
+    assert_eq!(out, b"42 42 ok\n");
+}
+
+#[test]
+fn run_repl_reports_an_error_without_leaking_partial_output_into_the_next_line() {
+    let mut f = Forth::new();
+    let mut out = Vec::new();
+    f.run_repl(
+        std::io::Cursor::new(b"1 . foo\n3 .\n".as_ref()),
+        &mut out,
+    )
+    .unwrap();
+
+    assert_eq!(
+        out,
+        b"1 UnknownWord { span: Span { start: 4, end: 7, line: 1, column: 5 } }\n3 ok\n".to_vec()
+    );
+}
@@ -1,17 +1,24 @@
+use std::borrow::Borrow;
+use std::io::{self, Read, Write};
+
 /// A munger which XORs a key with some data
 #[derive(Clone)]
-pub struct Xorcism<'a, Key> {
-    key: &'a Key
+pub struct Xorcism<'a> {
+    key: &'a [u8],
+    pos: usize,
 }
 
-impl<'a, Key> Xorcism<'a, Key> {
+impl<'a> Xorcism<'a> {
     /// Create a new Xorcism munger from a key
     ///
     /// Should accept anything which has a cheap conversion to a byte slice.
-    pub fn new(key: &'a Key) -> Self 
-    where Key: Iterator<Item = u8>
+    pub fn new<Key>(key: &'a Key) -> Self
+    where
+        Key: AsRef<[u8]> + ?Sized,
     {
-        Self{key}
+        let key = key.as_ref();
+        assert!(!key.is_empty(), "Xorcism key must not be empty");
+        Self { key, pos: 0 }
     }
 
     /// XOR each byte of the input buffer with a byte from the key.
@@ -19,7 +26,10 @@ impl<'a, Key> Xorcism<'a, Key> {
     /// Note that this is stateful: repeated calls are likely to produce different results,
     /// even with identical inputs.
     pub fn munge_in_place(&mut self, data: &mut [u8]) {
-        unimplemented!()
+        for byte in data.iter_mut() {
+            *byte ^= self.key[self.pos];
+            self.pos = (self.pos + 1) % self.key.len();
+        }
     }
 
     /// XOR each byte of the data with a byte from the key.
@@ -29,10 +39,71 @@ impl<'a, Key> Xorcism<'a, Key> {
     ///
     /// Should accept anything which has a cheap conversion to a byte iterator.
     /// Shouldn't matter whether the byte iterator's values are owned or borrowed.
-    pub fn munge<Data>(&mut self, data: Data) -> impl Iterator<Item = u8> {
-        unimplemented!();
-        // this empty iterator silences a compiler complaint that
-        // () doesn't implement ExactSizeIterator
-        std::iter::empty()
+    pub fn munge<Data>(&mut self, data: Data) -> impl Iterator<Item = u8>
+    where
+        Data: IntoIterator,
+        Data::Item: Borrow<u8>,
+    {
+        let key = self.key;
+        let mut pos = self.pos;
+        let munged: Vec<u8> = data
+            .into_iter()
+            .map(move |b| {
+                let out = *b.borrow() ^ key[pos % key.len()];
+                pos = (pos + 1) % key.len();
+                out
+            })
+            .collect();
+        self.pos = (self.pos + munged.len()) % self.key.len();
+        munged.into_iter()
+    }
+
+    /// Wrap `source` in a reader that munges the bytes read through it,
+    /// preserving keystream position across successive `read` calls.
+    pub fn reader<R: Read + 'a>(self, source: R) -> impl Read + 'a {
+        XorReader {
+            xorcism: self,
+            source,
+        }
+    }
+
+    /// Wrap `sink` in a writer that munges bytes before forwarding them,
+    /// preserving keystream position across successive `write` calls.
+    pub fn writer<W: Write + 'a>(self, sink: W) -> impl Write + 'a {
+        XorWriter {
+            xorcism: self,
+            sink,
+        }
     }
-}
\ No newline at end of file
+}
+
+struct XorReader<'a, R> {
+    xorcism: Xorcism<'a>,
+    source: R,
+}
+
+impl<'a, R: Read> Read for XorReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.source.read(buf)?;
+        self.xorcism.munge_in_place(&mut buf[..n]);
+        Ok(n)
+    }
+}
+
+struct XorWriter<'a, W> {
+    xorcism: Xorcism<'a>,
+    sink: W,
+}
+
+impl<'a, W: Write> Write for XorWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut munged = buf.to_vec();
+        self.xorcism.munge_in_place(&mut munged);
+        self.sink.write_all(&munged)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
@@ -0,0 +1,85 @@
+use std::io::{Cursor, Read, Write};
+
+use xorcism::Xorcism;
+
+#[test]
+fn munge_xors_each_byte_with_the_key_cycled_as_needed() {
+    let mut xorcism = Xorcism::new(&[1, 2, 3][..]);
+    let munged: Vec<u8> = xorcism.munge(vec![0, 0, 0, 0, 0]).collect();
+    assert_eq!(munged, vec![1, 2, 3, 1, 2]);
+}
+
+#[test]
+fn munge_accepts_a_key_that_is_cheaply_convertible_to_a_byte_slice() {
+    let mut xorcism = Xorcism::new("key");
+    let munged: Vec<u8> = xorcism.munge(b"abc".to_vec()).collect();
+    assert_eq!(munged, vec![b'a' ^ b'k', b'b' ^ b'e', b'c' ^ b'y']);
+}
+
+#[test]
+fn repeated_munge_calls_continue_the_keystream_instead_of_restarting_it() {
+    let mut xorcism = Xorcism::new(&[1, 2, 3][..]);
+    let first: Vec<u8> = xorcism.munge(vec![0, 0]).collect();
+    let second: Vec<u8> = xorcism.munge(vec![0, 0, 0]).collect();
+
+    assert_eq!(first, vec![1, 2]);
+    assert_eq!(second, vec![3, 1, 2]);
+}
+
+#[test]
+fn munging_twice_with_the_same_starting_state_round_trips_back_to_the_original_data() {
+    let data = b"Attack at dawn".to_vec();
+
+    let mut encoder = Xorcism::new(&[1, 2, 3][..]);
+    let encoded: Vec<u8> = encoder.munge(data.clone()).collect();
+
+    let mut decoder = Xorcism::new(&[1, 2, 3][..]);
+    let decoded: Vec<u8> = decoder.munge(encoded).collect();
+
+    assert_eq!(decoded, data);
+}
+
+#[test]
+fn reader_munges_bytes_read_through_it_and_preserves_position_across_reads() {
+    let xorcism = Xorcism::new(&[1, 2, 3][..]);
+    let mut reader = xorcism.reader(Cursor::new(vec![0u8; 5]));
+
+    let mut first = [0u8; 2];
+    reader.read_exact(&mut first).unwrap();
+    let mut rest = [0u8; 3];
+    reader.read_exact(&mut rest).unwrap();
+
+    assert_eq!(first, [1, 2]);
+    assert_eq!(rest, [3, 1, 2]);
+}
+
+#[test]
+fn writer_munges_bytes_before_forwarding_them_and_preserves_position_across_writes() {
+    let xorcism = Xorcism::new(&[1, 2, 3][..]);
+    let mut sink = Vec::new();
+    {
+        let mut writer = xorcism.writer(&mut sink);
+        writer.write_all(&[0, 0]).unwrap();
+        writer.write_all(&[0, 0, 0]).unwrap();
+    }
+
+    assert_eq!(sink, vec![1, 2, 3, 1, 2]);
+}
+
+#[test]
+fn reader_and_writer_round_trip_data_through_the_same_key() {
+    let data = b"the quick brown fox".to_vec();
+
+    let encoder = Xorcism::new("secret");
+    let mut sink = Vec::new();
+    encoder.writer(&mut sink).write_all(&data).unwrap();
+
+    let decoder = Xorcism::new("secret");
+    let mut decoded = Vec::new();
+    decoder
+        .reader(Cursor::new(sink))
+        .read_to_end(&mut decoded)
+        .unwrap();
+
+    assert_eq!(decoded, data);
+}